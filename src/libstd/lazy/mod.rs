@@ -1,5 +1,7 @@
 //! Lazy values and one-time initialization of static data.
 
+pub mod race;
+
 use crate::{
     cell::{Cell, UnsafeCell},
     fmt,
@@ -7,10 +9,15 @@ use crate::{
     mem::{self, MaybeUninit},
     ops::{Deref, Drop},
     panic::{RefUnwindSafe, UnwindSafe},
-    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, Ordering},
     thread::{self, Thread},
 };
 
+#[cfg(not(feature = "once_cell_parking_lot"))]
+use crate::sync::atomic::AtomicUsize;
+#[cfg(feature = "once_cell_parking_lot")]
+use crate::sync::atomic::AtomicU8;
+
 #[doc(inline)]
 #[unstable(feature = "once_cell", issue = "68198")]
 pub use core::lazy::*;
@@ -42,10 +49,13 @@ pub use core::lazy::*;
 /// ```
 #[unstable(feature = "once_cell", issue = "68198")]
 pub struct SyncOnceCell<T> {
-    // This `state` word is actually an encoded version of just a pointer to a
-    // `Waiter`, so we add the `PhantomData` appropriately.
-    state_and_queue: AtomicUsize,
+    // On the default backend this `state` word is actually an encoded version of
+    // a pointer to a `Waiter`, so we add the `PhantomData` appropriately. The
+    // `once_cell_parking_lot` backend doesn't embed a waiter list in `state`, so
+    // it has no pointer provenance to account for.
+    #[cfg(not(feature = "once_cell_parking_lot"))]
     _marker: PhantomData<*mut Waiter>,
+    state_and_queue: State,
     // Whether or not the value is initialized is tracked by `state_and_queue`.
     value: UnsafeCell<MaybeUninit<T>>,
 }
@@ -77,6 +87,7 @@ impl<T: fmt::Debug> fmt::Debug for SyncOnceCell<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.get() {
             Some(v) => f.debug_tuple("Once").field(v).finish(),
+            None if self.is_poisoned() => f.write_str("Once(Poisoned)"),
             None => f.write_str("Once(Uninit)"),
         }
     }
@@ -120,8 +131,9 @@ impl<T> SyncOnceCell<T> {
     #[unstable(feature = "once_cell", issue = "68198")]
     pub const fn new() -> SyncOnceCell<T> {
         SyncOnceCell {
-            state_and_queue: AtomicUsize::new(INCOMPLETE),
+            #[cfg(not(feature = "once_cell_parking_lot"))]
             _marker: PhantomData,
+            state_and_queue: State::new(INCOMPLETE),
             value: UnsafeCell::new(MaybeUninit::uninit()),
         }
     }
@@ -272,6 +284,95 @@ impl<T> SyncOnceCell<T> {
         Ok(unsafe { self.get_unchecked() })
     }
 
+    /// Blocks the current thread until the cell is initialized.
+    ///
+    /// This is useful when you want to wait for the result of another
+    /// thread's initializer without racing it with one of your own: unlike
+    /// `get_or_init`, `wait` never runs an initializing closure, it just
+    /// parks until the cell becomes `COMPLETE` and then returns the value
+    /// that's there.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cell is poisoned, i.e. some thread's call to
+    /// `get_or_init`/`get_or_try_init`/`set` panicked while initializing it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #![feature(once_cell)]
+    ///
+    /// use std::lazy::SyncOnceCell;
+    /// use std::thread;
+    ///
+    /// static CELL: SyncOnceCell<i32> = SyncOnceCell::new();
+    ///
+    /// let t = thread::spawn(|| {
+    ///     assert_eq!(CELL.wait(), &92);
+    /// });
+    /// CELL.set(92).unwrap();
+    /// t.join().unwrap();
+    /// ```
+    #[unstable(feature = "once_cell", issue = "68198")]
+    pub fn wait(&self) -> &T {
+        let mut state_and_queue = self.state_and_queue.load(Ordering::Acquire);
+        loop {
+            match state_and_queue & STATE_MASK {
+                COMPLETE => break,
+                POISONED => panic!("SyncOnceCell instance has previously been poisoned"),
+                RUNNING => {
+                    wait(&self.state_and_queue, state_and_queue);
+                    state_and_queue = self.state_and_queue.load(Ordering::Acquire);
+                }
+                // Nobody has started initializing the cell yet: there's nothing to
+                // park on, so just yield and check again.
+                _ => {
+                    thread::yield_now();
+                    state_and_queue = self.state_and_queue.load(Ordering::Acquire);
+                }
+            }
+        }
+
+        // Safe b/c we only broke out of the loop above once we observed `COMPLETE`.
+        unsafe { self.get_unchecked() }
+    }
+
+    /// Returns `true` if this cell's initializer previously panicked, leaving
+    /// the cell poisoned.
+    ///
+    /// A poisoned cell can no longer be initialized with `get_or_init`,
+    /// `get_or_try_init` or `set`: they all panic until the poison is cleared
+    /// with `clear_poison`. This mirrors `Mutex`'s poisoning.
+    #[unstable(feature = "once_cell", issue = "68198")]
+    pub fn is_poisoned(&self) -> bool {
+        self.state_and_queue.load(Ordering::SeqCst) == POISONED
+    }
+
+    /// Clears a cell's poisoned state, returning it to an uninitialized state
+    /// so a later call to `get_or_init`/`get_or_try_init`/`set` can retry
+    /// initialization.
+    ///
+    /// Has no effect if the cell isn't currently poisoned.
+    #[unstable(feature = "once_cell", issue = "68198")]
+    pub fn clear_poison(&self) {
+        self.clear_poison_with(|| {});
+    }
+
+    /// Clears a cell's poisoned state like `clear_poison`, additionally
+    /// running `f` with exclusive rights to the cell before any other
+    /// thread can observe it as cleared.
+    ///
+    /// This lets a caller (namely `SyncLazy::clear_poison`) repopulate
+    /// state that's only valid once the poison is gone, without racing a
+    /// concurrent `get_or_init`/`wait`/`clear_poison` caller over it: `f`
+    /// runs behind the same `RUNNING` state transition (and the same
+    /// wake-up-on-drop guard) that a fresh `get_or_init` uses while its
+    /// own initializer runs. No-op, and `f` is never called, if the cell
+    /// wasn't poisoned or another thread already won the race to clear it.
+    fn clear_poison_with(&self, f: impl FnOnce()) {
+        reinitialize_poisoned(&self.state_and_queue, f);
+    }
+
     /// Consumes the `Once`, returning the wrapped value. Returns
     /// `None` if the cell was empty.
     ///
@@ -300,11 +401,53 @@ impl<T> SyncOnceCell<T> {
         inner
     }
 
-    /// Takes the wrapped value out of a `Once`.
-    /// Afterwards the cell is no longer initialized.
+    /// Takes the value out of this `SyncOnceCell`, moving it back to an uninitialized
+    /// state.
+    ///
+    /// Has no effect and returns `None` if the `SyncOnceCell` hasn't been initialized.
+    ///
+    /// Safety is guaranteed by requiring a mutable reference.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #![feature(once_cell)]
+    ///
+    /// use std::lazy::SyncOnceCell;
+    ///
+    /// let mut cell: SyncOnceCell<String> = SyncOnceCell::new();
+    /// assert_eq!(cell.take(), None);
+    ///
+    /// let mut cell = SyncOnceCell::new();
+    /// cell.set("hello".to_string()).unwrap();
+    /// assert_eq!(cell.take(), Some("hello".to_string()));
+    /// assert_eq!(cell.get(), None);
+    /// ```
+    #[unstable(feature = "once_cell", issue = "68198")]
+    pub fn take(&mut self) -> Option<T> {
+        if self.is_initialized() {
+            // Safety: `&mut self` guarantees that no other threads are concurrently
+            // accessing the cell, so it's fine to move the value out below and only
+            // then reset `state_and_queue` back to `INCOMPLETE`. A poisoned cell
+            // never held a value, so it's left untouched here and stays poisoned
+            // until a caller explicitly opts in via `clear_poison`.
+            let value = unsafe { self.take_inner() };
+            self.state_and_queue = State::new(INCOMPLETE);
+            value
+        } else {
+            None
+        }
+    }
+
+    /// Takes the wrapped value out of a `Once`, leaving its state untouched.
+    /// Afterwards the cell reports as still initialized unless the caller
+    /// also resets `state_and_queue`, which is the right thing to do when
+    /// the cell is about to be freed (`into_inner`, `drop`) but would be
+    /// wrong for `take`, which resets it separately so the cell can be
+    /// reused.
     ///
-    /// Safety: The cell must now be free'd WITHOUT dropping. No other usages of the cell
-    /// are valid. Only used by `into_inner` and `drop`.
+    /// Safety: The `&mut self` guarantees no other thread can observe us
+    /// taking out the wrapped value.
     unsafe fn take_inner(&mut self) -> Option<T> {
         // The mutable reference guarantees there are no other threads that can observe us
         // taking out the wrapped value.
@@ -379,12 +522,49 @@ impl<T> Drop for SyncOnceCell<T> {
 // This should be uncopypasted once we decide the right way to handle panics.
 // Do we want to effectively move the `Once` synchronization here and make `Once`
 // a newtype: `pub struct Once(lazy::SyncOnceCell<()>)`?
+//
+// Two backends are available, selected by the `once_cell_parking_lot` Cargo
+// feature:
+//
+// * The default backend (this module's `State = AtomicUsize`) keeps an
+//   intrusive, lock-free list of `Waiter`s linked through the unused low bits
+//   of `state_and_queue` itself.
+// * The `once_cell_parking_lot` backend (`State = AtomicU8`) has no queue to
+//   speak of: blocked threads park on a key derived from the cell's own
+//   address in a global, `parking_lot_core`-managed table, so `state_and_queue`
+//   only ever needs to hold the two state bits.
+#[cfg(not(feature = "once_cell_parking_lot"))]
+type State = AtomicUsize;
+#[cfg(feature = "once_cell_parking_lot")]
+type State = AtomicU8;
+
+#[cfg(not(feature = "once_cell_parking_lot"))]
 const INCOMPLETE: usize = 0x0;
+#[cfg(not(feature = "once_cell_parking_lot"))]
 const RUNNING: usize = 0x1;
+#[cfg(not(feature = "once_cell_parking_lot"))]
 const COMPLETE: usize = 0x2;
-
+// A terminal state: the initializer panicked instead of returning, so the
+// value was never written. Unlike `INCOMPLETE`, a poisoned cell does not
+// silently retry `f` on the next call; it stays poisoned until a caller
+// opts in via `clear_poison`. Mirrors `sync::Mutex`'s poisoning.
+#[cfg(not(feature = "once_cell_parking_lot"))]
+const POISONED: usize = 0x3;
+#[cfg(not(feature = "once_cell_parking_lot"))]
 const STATE_MASK: usize = 0x3;
 
+#[cfg(feature = "once_cell_parking_lot")]
+const INCOMPLETE: u8 = 0x0;
+#[cfg(feature = "once_cell_parking_lot")]
+const RUNNING: u8 = 0x1;
+#[cfg(feature = "once_cell_parking_lot")]
+const COMPLETE: u8 = 0x2;
+#[cfg(feature = "once_cell_parking_lot")]
+const POISONED: u8 = 0x3;
+#[cfg(feature = "once_cell_parking_lot")]
+const STATE_MASK: u8 = 0x3;
+
+#[cfg(not(feature = "once_cell_parking_lot"))]
 #[repr(align(4))]
 struct Waiter {
     thread: Cell<Option<Thread>>,
@@ -392,11 +572,13 @@ struct Waiter {
     next: *const Waiter,
 }
 
+#[cfg(not(feature = "once_cell_parking_lot"))]
 struct WaiterQueue<'a> {
     state_and_queue: &'a AtomicUsize,
     set_state_on_drop_to: usize,
 }
 
+#[cfg(not(feature = "once_cell_parking_lot"))]
 impl Drop for WaiterQueue<'_> {
     fn drop(&mut self) {
         let state_and_queue =
@@ -417,12 +599,14 @@ impl Drop for WaiterQueue<'_> {
     }
 }
 
+#[cfg(not(feature = "once_cell_parking_lot"))]
 fn initialize_inner(my_state_and_queue: &AtomicUsize, init: &mut dyn FnMut() -> bool) -> bool {
     let mut state_and_queue = my_state_and_queue.load(Ordering::Acquire);
 
     loop {
         match state_and_queue {
             COMPLETE => return true,
+            POISONED => panic!("SyncOnceCell instance has previously been poisoned"),
             INCOMPLETE => {
                 let old = my_state_and_queue.compare_and_swap(
                     state_and_queue,
@@ -433,9 +617,12 @@ fn initialize_inner(my_state_and_queue: &AtomicUsize, init: &mut dyn FnMut() ->
                     state_and_queue = old;
                     continue;
                 }
+                // Default to `POISONED`: if `init` panics, the drop impl below
+                // runs during unwinding and leaves the cell poisoned instead of
+                // silently reverting to `INCOMPLETE`.
                 let mut waiter_queue = WaiterQueue {
                     state_and_queue: my_state_and_queue,
-                    set_state_on_drop_to: INCOMPLETE,
+                    set_state_on_drop_to: POISONED,
                 };
                 let success = init();
 
@@ -451,6 +638,24 @@ fn initialize_inner(my_state_and_queue: &AtomicUsize, init: &mut dyn FnMut() ->
     }
 }
 
+#[cfg(not(feature = "once_cell_parking_lot"))]
+fn reinitialize_poisoned(my_state_and_queue: &AtomicUsize, f: impl FnOnce()) {
+    let old = my_state_and_queue.compare_and_swap(POISONED, RUNNING, Ordering::Acquire);
+    if old != POISONED {
+        // We lost the race: another thread already cleared (or is clearing)
+        // this poison, so there's nothing left for us to do.
+        return;
+    }
+
+    // Default to `POISONED`: if `f` panics, the drop impl below runs during
+    // unwinding and leaves the cell poisoned, just like a failed initializer.
+    let mut waiter_queue =
+        WaiterQueue { state_and_queue: my_state_and_queue, set_state_on_drop_to: POISONED };
+    f();
+    waiter_queue.set_state_on_drop_to = INCOMPLETE;
+}
+
+#[cfg(not(feature = "once_cell_parking_lot"))]
 fn wait(state_and_queue: &AtomicUsize, mut current_state: usize) {
     loop {
         if current_state & STATE_MASK != RUNNING {
@@ -477,6 +682,93 @@ fn wait(state_and_queue: &AtomicUsize, mut current_state: usize) {
     }
 }
 
+// The `parking_lot_core`-backed implementation below trades the intrusive
+// `Waiter` queue for `parking_lot_core`'s own keyed-futex table, which is
+// addressed by the cell's memory address rather than by a pointer embedded
+// in `state_and_queue`. This means `SyncOnceCell` no longer needs to carry
+// `PhantomData<*mut Waiter>` and shrinks its state word down to a single
+// `AtomicU8`, at the cost of pulling in `parking_lot_core` as a dependency
+// and no longer being able to inline the waiter list for free. `ParkGuard`
+// plays the same unwind-safety role here that `WaiterQueue` plays for the
+// default backend.
+#[cfg(feature = "once_cell_parking_lot")]
+fn initialize_inner(state: &AtomicU8, init: &mut dyn FnMut() -> bool) -> bool {
+    loop {
+        match state.compare_and_swap(INCOMPLETE, RUNNING, Ordering::Acquire) {
+            INCOMPLETE => break,
+            COMPLETE => return true,
+            POISONED => panic!("SyncOnceCell instance has previously been poisoned"),
+            _ => wait(state, RUNNING),
+        }
+    }
+
+    // Default to `POISONED`: if `init` panics, `ParkGuard`'s `Drop` impl runs
+    // during unwinding just like `WaiterQueue`'s does for the default backend,
+    // leaving the cell poisoned (and waking up anyone parked on it) instead
+    // of leaving it stuck `RUNNING` forever.
+    let mut guard = ParkGuard { state, set_state_on_drop_to: POISONED };
+    let success = init();
+    guard.set_state_on_drop_to = if success { COMPLETE } else { INCOMPLETE };
+    success
+}
+
+#[cfg(feature = "once_cell_parking_lot")]
+struct ParkGuard<'a> {
+    state: &'a AtomicU8,
+    set_state_on_drop_to: u8,
+}
+
+#[cfg(feature = "once_cell_parking_lot")]
+impl Drop for ParkGuard<'_> {
+    fn drop(&mut self) {
+        self.state.store(self.set_state_on_drop_to, Ordering::Release);
+        // Everyone else observes the new state, then gets woken up in one
+        // shot via `unpark_all` on our address key.
+        unsafe {
+            parking_lot_core::unpark_all(key(self.state), parking_lot_core::DEFAULT_UNPARK_TOKEN);
+        }
+    }
+}
+
+#[cfg(feature = "once_cell_parking_lot")]
+fn reinitialize_poisoned(state: &AtomicU8, f: impl FnOnce()) {
+    let old = state.compare_and_swap(POISONED, RUNNING, Ordering::Acquire);
+    if old != POISONED {
+        // We lost the race: another thread already cleared (or is clearing)
+        // this poison, so there's nothing left for us to do.
+        return;
+    }
+
+    // Default to `POISONED`: if `f` panics, `ParkGuard`'s `Drop` impl runs
+    // during unwinding and leaves the cell poisoned, just like a failed
+    // initializer.
+    let mut guard = ParkGuard { state, set_state_on_drop_to: POISONED };
+    f();
+    guard.set_state_on_drop_to = INCOMPLETE;
+}
+
+#[cfg(feature = "once_cell_parking_lot")]
+fn wait(state: &AtomicU8, waiting_for: u8) {
+    let validate = || state.load(Ordering::Relaxed) == waiting_for;
+    let before_sleep = || {};
+    let timed_out = |_, _| {};
+    unsafe {
+        parking_lot_core::park(
+            key(state),
+            validate,
+            before_sleep,
+            timed_out,
+            parking_lot_core::DEFAULT_PARK_TOKEN,
+            None,
+        );
+    }
+}
+
+#[cfg(feature = "once_cell_parking_lot")]
+fn key(state: &AtomicU8) -> usize {
+    state as *const AtomicU8 as usize
+}
+
 /// A value which is initialized on the first access.
 ///
 /// This type is a thread-safe `Lazy`, and can be used in statics.
@@ -544,6 +836,13 @@ impl<T, F> SyncLazy<T, F> {
     pub const fn new(f: F) -> SyncLazy<T, F> {
         SyncLazy { cell: SyncOnceCell::new(), init: Cell::new(Some(f)) }
     }
+
+    /// Returns `true` if this lazy value's initializer has previously
+    /// panicked, leaving it poisoned.
+    #[unstable(feature = "once_cell", issue = "68198")]
+    pub fn is_poisoned(this: &SyncLazy<T, F>) -> bool {
+        this.cell.is_poisoned()
+    }
 }
 
 impl<T, F: FnOnce() -> T> SyncLazy<T, F> {
@@ -551,6 +850,12 @@ impl<T, F: FnOnce() -> T> SyncLazy<T, F> {
     /// returns a reference to result. This is equivalent
     /// to the `Deref` impl, but is explicit.
     ///
+    /// # Panics
+    ///
+    /// Panics if this lazy value is poisoned, i.e. a previous call to
+    /// `force` panicked while running the initializer. Use `clear_poison`
+    /// to recover it with a fresh initializer.
+    ///
     /// # Examples
     ///
     /// ```
@@ -567,9 +872,30 @@ impl<T, F: FnOnce() -> T> SyncLazy<T, F> {
     pub fn force(this: &SyncLazy<T, F>) -> &T {
         this.cell.get_or_init(|| match this.init.take() {
             Some(f) => f(),
-            None => panic!("Lazy instance has previously been poisoned"),
+            // `init` is only ever `None` while `this.cell` is poisoned, in which
+            // case `get_or_init` itself panics before this closure runs again.
+            None => unreachable!(),
         })
     }
+
+    /// Clears a poisoned lazy value and re-arms it with `f`, so that the next
+    /// call to `force` (or dereference) runs `f` instead of panicking.
+    ///
+    /// The original initializing closure was consumed by the panicking call
+    /// to `force`, so the caller has to supply a new one; this mirrors
+    /// `Mutex::clear_poison`, except that a fresh initializer is required
+    /// alongside it.
+    ///
+    /// Has no effect if this lazy value isn't currently poisoned.
+    #[unstable(feature = "once_cell", issue = "68198")]
+    pub fn clear_poison(this: &SyncLazy<T, F>, f: F) {
+        // `init` is only ever touched from here and from the winning thread
+        // inside `force`'s `get_or_init` closure, and `clear_poison_with`
+        // guarantees we run while exclusively holding the cell (no other
+        // `force`/`clear_poison` caller can observe the cell as cleared
+        // until after this closure returns), so this write can't race.
+        this.cell.clear_poison_with(|| this.init.set(Some(f)));
+    }
 }
 
 #[unstable(feature = "once_cell", issue = "68198")]
@@ -587,3 +913,82 @@ impl<T: Default> Default for SyncLazy<T> {
         SyncLazy::new(T::default)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::panic;
+
+    #[test]
+    fn sync_once_cell_take_resets_an_initialized_cell() {
+        let mut cell = SyncOnceCell::new();
+        cell.set("hello".to_string()).unwrap();
+
+        assert_eq!(cell.take(), Some("hello".to_string()));
+        assert_eq!(cell.get(), None);
+
+        // The cell is back to `INCOMPLETE`, so it can be reinitialized.
+        assert_eq!(*cell.get_or_init(|| "world".to_string()), "world".to_string());
+    }
+
+    #[test]
+    fn sync_once_cell_take_on_uninitialized_cell_is_a_no_op() {
+        let mut cell: SyncOnceCell<String> = SyncOnceCell::new();
+        assert_eq!(cell.take(), None);
+        assert_eq!(cell.get(), None);
+    }
+
+    #[test]
+    fn sync_once_cell_panicking_initializer_poisons_the_cell() {
+        let cell = SyncOnceCell::new();
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            cell.get_or_init(|| -> i32 { panic!("boom") });
+        }));
+        assert!(result.is_err());
+        assert!(cell.is_poisoned());
+        assert_eq!(cell.get(), None);
+
+        cell.clear_poison();
+        assert!(!cell.is_poisoned());
+        assert_eq!(*cell.get_or_init(|| 92), 92);
+    }
+
+    #[test]
+    fn sync_lazy_panicking_initializer_poisons_and_recovers() {
+        let lazy: SyncLazy<i32, _> = SyncLazy::new(|| panic!("boom"));
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            SyncLazy::force(&lazy);
+        }));
+        assert!(result.is_err());
+        assert!(SyncLazy::is_poisoned(&lazy));
+
+        SyncLazy::clear_poison(&lazy, || 92);
+        assert!(!SyncLazy::is_poisoned(&lazy));
+        assert_eq!(*SyncLazy::force(&lazy), 92);
+    }
+
+    #[cfg(feature = "once_cell_parking_lot")]
+    #[test]
+    fn sync_once_cell_parking_lot_backend_wakes_waiting_threads() {
+        use crate::sync::Arc;
+
+        let cell = Arc::new(SyncOnceCell::new());
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let cell = Arc::clone(&cell);
+                thread::spawn(move || {
+                    // Every thread but the first parks on `wait()` in
+                    // `initialize_inner` until the winner's `ParkGuard` stores
+                    // `COMPLETE` and unparks them.
+                    *cell.get_or_init(|| {
+                        thread::yield_now();
+                        i
+                    })
+                })
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        let winner = results[0];
+        assert!(results.into_iter().all(|v| v == winner));
+    }
+}
@@ -0,0 +1,253 @@
+//! Race-y versions of once cells.
+//!
+//! These types never park a thread waiting for another thread to finish
+//! initializing the cell: instead, if two threads race to run the
+//! initializer, both run it to completion and the loser's result is simply
+//! discarded. This trades the serialization that `SyncOnceCell` guarantees
+//! for a lock-free `get_or_init`, which suits cheap, idempotent
+//! initializers (interning ids, looking up a handle) better than blocking
+//! ones.
+
+use crate::{
+    num::NonZeroUsize,
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+/// A thread-safe cell that can be written to only once, specialized for
+/// storing a `NonZeroUsize`.
+///
+/// Unlike `SyncOnceCell`, this never blocks: if several threads race to
+/// call `get_or_init`, each runs its initializer, and whichever finishes
+/// first wins, with the other results simply discarded.
+///
+/// # Example
+///
+/// ```
+/// #![feature(once_cell)]
+///
+/// use std::lazy::race::OnceNonZeroUsize;
+///
+/// static CELL: OnceNonZeroUsize = OnceNonZeroUsize::new();
+/// assert_eq!(CELL.get(), None);
+///
+/// std::thread::spawn(|| {
+///     let value = CELL.get_or_init(|| NonZeroUsize::new(92).unwrap());
+///     assert_eq!(value.get(), 92);
+/// }).join().unwrap();
+///
+/// let value = CELL.get_or_init(|| unreachable!());
+/// assert_eq!(value.get(), 92);
+/// ```
+#[unstable(feature = "once_cell", issue = "68198")]
+pub struct OnceNonZeroUsize {
+    inner: AtomicUsize,
+}
+
+#[unstable(feature = "once_cell", issue = "68198")]
+impl OnceNonZeroUsize {
+    /// Creates a new empty cell.
+    #[unstable(feature = "once_cell", issue = "68198")]
+    pub const fn new() -> OnceNonZeroUsize {
+        OnceNonZeroUsize { inner: AtomicUsize::new(0) }
+    }
+
+    /// Gets the underlying value.
+    #[unstable(feature = "once_cell", issue = "68198")]
+    pub fn get(&self) -> Option<NonZeroUsize> {
+        let val = self.inner.load(Ordering::Acquire);
+        NonZeroUsize::new(val)
+    }
+
+    /// Sets the contents of this cell to `value`.
+    ///
+    /// Returns `Ok(())` if the cell was empty and `Err(())` if it was
+    /// full.
+    #[unstable(feature = "once_cell", issue = "68198")]
+    pub fn set(&self, value: NonZeroUsize) -> Result<(), ()> {
+        let exchange =
+            self.inner.compare_exchange(0, value.get(), Ordering::AcqRel, Ordering::Acquire);
+        match exchange {
+            Ok(_) => Ok(()),
+            Err(_) => Err(()),
+        }
+    }
+
+    /// Gets the contents of the cell, initializing it with `f` if the
+    /// cell was empty.
+    ///
+    /// If several threads concurrently run `get_or_init`, more than one
+    /// `f` may be called, but only one value will be stored: the losing
+    /// threads simply discard the value their `f` computed.
+    ///
+    /// # Panics
+    ///
+    /// If `f` panics, the panic is propagated to the caller, and the cell
+    /// remains uninitialized.
+    ///
+    /// It is safe, if wasteful, to reentrantly initialize the cell from `f`:
+    /// since this cell never blocks, the reentrant call just computes its
+    /// own value and loses the race to store it.
+    #[unstable(feature = "once_cell", issue = "68198")]
+    pub fn get_or_init<F>(&self, f: F) -> NonZeroUsize
+    where
+        F: FnOnce() -> NonZeroUsize,
+    {
+        if let Some(val) = self.get() {
+            return val;
+        }
+        let val = f();
+        self.set(val).ok();
+        self.get().unwrap_or(val)
+    }
+}
+
+/// A thread-safe cell that can be written to only once, specialized for
+/// storing a boxed value.
+///
+/// Unlike `SyncOnceCell`, this never blocks: if several threads race to
+/// call `get_or_init`, each runs its initializer to completion, and the
+/// boxes that lose the race are dropped instead of published.
+///
+/// # Example
+///
+/// ```
+/// #![feature(once_cell)]
+///
+/// use std::lazy::race::OnceBox;
+///
+/// static CELL: OnceBox<String> = OnceBox::new();
+/// assert!(CELL.get().is_none());
+///
+/// std::thread::spawn(|| {
+///     let value: &String = CELL.get_or_init(|| Box::new("Hello, World!".to_string()));
+///     assert_eq!(value, "Hello, World!");
+/// }).join().unwrap();
+///
+/// let value: Option<&String> = CELL.get();
+/// assert!(value.is_some());
+/// assert_eq!(value.unwrap().as_str(), "Hello, World!");
+/// ```
+#[unstable(feature = "once_cell", issue = "68198")]
+pub struct OnceBox<T> {
+    inner: AtomicPtr<T>,
+}
+
+#[unstable(feature = "once_cell", issue = "68198")]
+impl<T> Default for OnceBox<T> {
+    fn default() -> OnceBox<T> {
+        OnceBox::new()
+    }
+}
+
+#[unstable(feature = "once_cell", issue = "68198")]
+impl<T> Drop for OnceBox<T> {
+    fn drop(&mut self) {
+        let ptr = *self.inner.get_mut();
+        if !ptr.is_null() {
+            unsafe { drop(Box::from_raw(ptr)) }
+        }
+    }
+}
+
+#[unstable(feature = "once_cell", issue = "68198")]
+impl<T> OnceBox<T> {
+    /// Creates a new empty cell.
+    #[unstable(feature = "once_cell", issue = "68198")]
+    pub const fn new() -> OnceBox<T> {
+        OnceBox { inner: AtomicPtr::new(ptr::null_mut()) }
+    }
+
+    /// Gets a reference to the underlying value.
+    #[unstable(feature = "once_cell", issue = "68198")]
+    pub fn get(&self) -> Option<&T> {
+        let ptr = self.inner.load(Ordering::Acquire);
+        if ptr.is_null() { None } else { Some(unsafe { &*ptr }) }
+    }
+
+    /// Gets the contents of the cell, initializing it with `f` if the
+    /// cell was empty.
+    ///
+    /// If several threads concurrently run `get_or_init`, more than one
+    /// `f` may be called, but only one box will be stored: the losing
+    /// threads' boxes are dropped instead of leaked.
+    ///
+    /// # Panics
+    ///
+    /// If `f` panics, the panic is propagated to the caller, and the cell
+    /// remains uninitialized.
+    ///
+    /// It is safe, if wasteful, to reentrantly initialize the cell from `f`:
+    /// since this cell never blocks, the reentrant call just computes its
+    /// own box and loses the race to store it.
+    #[unstable(feature = "once_cell", issue = "68198")]
+    pub fn get_or_init<F>(&self, f: F) -> &T
+    where
+        F: FnOnce() -> Box<T>,
+    {
+        let mut ptr = self.inner.load(Ordering::Acquire);
+
+        if ptr.is_null() {
+            let val = f();
+            let new_ptr = Box::into_raw(val);
+            let exchange = self.inner.compare_exchange(
+                ptr::null_mut(),
+                new_ptr,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            );
+            ptr = match exchange {
+                Ok(_) => new_ptr,
+                Err(old) => {
+                    // Someone else beat us to it: drop our losing box and
+                    // use theirs instead.
+                    unsafe { drop(Box::from_raw(new_ptr)) }
+                    old
+                }
+            };
+        }
+
+        unsafe { &*ptr }
+    }
+}
+
+#[unstable(feature = "once_cell", issue = "68198")]
+unsafe impl<T: Sync + Send> Sync for OnceBox<T> {}
+#[unstable(feature = "once_cell", issue = "68198")]
+unsafe impl<T: Send> Send for OnceBox<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::Arc;
+    use crate::thread;
+
+    #[test]
+    fn once_non_zero_usize_racing_threads_agree_on_a_winner() {
+        let cell = Arc::new(OnceNonZeroUsize::new());
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let cell = Arc::clone(&cell);
+                thread::spawn(move || cell.get_or_init(|| NonZeroUsize::new(i + 1).unwrap()))
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        let winner = results[0];
+        assert!(results.into_iter().all(|v| v == winner));
+        assert_eq!(cell.get(), Some(winner));
+    }
+
+    #[test]
+    fn once_box_racing_threads_agree_on_a_winner() {
+        let cell = Arc::new(OnceBox::new());
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let cell = Arc::clone(&cell);
+                thread::spawn(move || cell.get_or_init(|| Box::new(i)) as *const i32 as usize)
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        let winner = results[0];
+        assert!(results.into_iter().all(|v| v == winner));
+    }
+}